@@ -1,10 +1,45 @@
-use crate::db::{Drawing, RoomSettings, Snapshot, DB};
+use crate::crypto;
+use crate::db::{Drawing, RoomSettings, Snapshot, SnapshotHistoryEntry, MAIN_POOL};
+use crate::permissions::{self, EffectivePermissions, RoomMember};
+use rusqlite::OptionalExtension;
 use std::time::{SystemTime, UNIX_EPOCH};
 
 const AUTOSAVE_CREATED_BY: &str = "__autosave__";
 const AUTOSAVE_DEFAULT_NAME: &str = "Latest autosave snapshot";
 const AUTOSAVE_DEFAULT_DESCRIPTION: &str = "Automatically saved by Excalidraw";
 
+// Marks a column value as AES-256-GCM ciphertext rather than plaintext, so a
+// database with no passphrase set behaves exactly as before.
+const ENCRYPTED_PREFIX: &str = "enc1:";
+
+/// Encrypts `data` under the session key if the database is unlocked;
+/// otherwise returns it unchanged. Encryption is opt-in, so drawings saved
+/// before a passphrase is set stay readable.
+fn maybe_encrypt(data: &str) -> Result<String, String> {
+    let key = crypto::SESSION_KEY.lock().map_err(|e| e.to_string())?;
+    match *key {
+        Some(k) => Ok(format!("{}{}", ENCRYPTED_PREFIX, crypto::encrypt_string(&k, data)?)),
+        None => Ok(data.to_string()),
+    }
+}
+
+fn maybe_encrypt_opt(data: Option<String>) -> Result<Option<String>, String> {
+    data.map(|d| maybe_encrypt(&d)).transpose()
+}
+
+/// Decrypts `data` if it carries the encrypted-value marker, returning a
+/// clear error if the database is locked rather than garbage bytes.
+fn maybe_decrypt(data: &str) -> Result<String, String> {
+    match data.strip_prefix(ENCRYPTED_PREFIX) {
+        Some(ciphertext) => crypto::decrypt_string(&crypto::current_key()?, ciphertext),
+        None => Ok(data.to_string()),
+    }
+}
+
+fn maybe_decrypt_opt(data: Option<String>) -> Result<Option<String>, String> {
+    data.map(|d| maybe_decrypt(&d)).transpose()
+}
+
 #[tauri::command]
 pub fn save_drawing(name: String, data: String) -> Result<String, String> {
     let timestamp = SystemTime::now()
@@ -13,12 +48,13 @@ pub fn save_drawing(name: String, data: String) -> Result<String, String> {
         .as_secs() as i64;
 
     let id = uuid::Uuid::new_v4().to_string();
+    let stored_data = maybe_encrypt(&data)?;
 
-    let conn = DB.lock().map_err(|e| e.to_string())?;
+    let conn = MAIN_POOL.get().map_err(|e| e.to_string())?;
 
     conn.execute(
         "INSERT INTO drawings (id, name, data, created_at, updated_at) VALUES (?1, ?2, ?3, ?4, ?5)",
-        rusqlite::params![&id, &name, &data, timestamp, timestamp],
+        rusqlite::params![&id, &name, &stored_data, timestamp, timestamp],
     )
     .map_err(|e| e.to_string())?;
 
@@ -27,16 +63,14 @@ pub fn save_drawing(name: String, data: String) -> Result<String, String> {
 
 #[tauri::command]
 pub fn update_drawing(id: String, name: String, data: String) -> Result<(), String> {
-    let timestamp = SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .unwrap()
-        .as_secs() as i64;
+    let stored_data = maybe_encrypt(&data)?;
 
-    let conn = DB.lock().map_err(|e| e.to_string())?;
+    let conn = MAIN_POOL.get().map_err(|e| e.to_string())?;
 
+    // `updated_at` is stamped by the trg_drawings_set_updated_at trigger.
     conn.execute(
-        "UPDATE drawings SET name = ?1, data = ?2, updated_at = ?3 WHERE id = ?4",
-        rusqlite::params![&name, &data, timestamp, &id],
+        "UPDATE drawings SET name = ?1, data = ?2 WHERE id = ?3",
+        rusqlite::params![&name, &stored_data, &id],
     )
     .map_err(|e| e.to_string())?;
 
@@ -45,7 +79,7 @@ pub fn update_drawing(id: String, name: String, data: String) -> Result<(), Stri
 
 #[tauri::command]
 pub fn load_drawing(id: String) -> Result<Drawing, String> {
-    let conn = DB.lock().map_err(|e| e.to_string())?;
+    let conn = MAIN_POOL.get().map_err(|e| e.to_string())?;
 
     let drawing = conn
         .query_row(
@@ -63,12 +97,15 @@ pub fn load_drawing(id: String) -> Result<Drawing, String> {
         )
         .map_err(|e| e.to_string())?;
 
-    Ok(drawing)
+    Ok(Drawing {
+        data: maybe_decrypt(&drawing.data)?,
+        ..drawing
+    })
 }
 
 #[tauri::command]
 pub fn list_drawings() -> Result<Vec<Drawing>, String> {
-    let conn = DB.lock().map_err(|e| e.to_string())?;
+    let conn = MAIN_POOL.get().map_err(|e| e.to_string())?;
 
     let mut stmt = conn
         .prepare(
@@ -90,12 +127,20 @@ pub fn list_drawings() -> Result<Vec<Drawing>, String> {
         .collect::<Result<Vec<_>, _>>()
         .map_err(|e| e.to_string())?;
 
-    Ok(drawings)
+    drawings
+        .into_iter()
+        .map(|d| {
+            Ok(Drawing {
+                data: maybe_decrypt(&d.data)?,
+                ..d
+            })
+        })
+        .collect()
 }
 
 #[tauri::command]
 pub fn delete_drawing(id: String) -> Result<(), String> {
-    let conn = DB.lock().map_err(|e| e.to_string())?;
+    let conn = MAIN_POOL.get().map_err(|e| e.to_string())?;
 
     conn.execute("DELETE FROM drawings WHERE id = ?1", rusqlite::params![&id])
         .map_err(|e| e.to_string())?;
@@ -108,6 +153,7 @@ pub fn delete_drawing(id: String) -> Result<(), String> {
 #[tauri::command]
 pub fn save_snapshot(
     room_id: String,
+    user_id: String,
     name: Option<String>,
     description: Option<String>,
     thumbnail: Option<String>,
@@ -120,34 +166,23 @@ pub fn save_snapshot(
         .as_secs() as i64;
 
     let id = uuid::Uuid::new_v4().to_string();
+    let stored_data = maybe_encrypt(&data)?;
+    let stored_thumbnail = maybe_encrypt_opt(thumbnail)?;
 
-    let conn = DB.lock().map_err(|e| e.to_string())?;
-
-    // Get room settings to check max snapshots
-    let settings = get_room_settings_internal(&conn, &room_id)?;
+    let conn = MAIN_POOL.get().map_err(|e| e.to_string())?;
 
-    // Count existing snapshots
-    let count: i32 = conn
-        .query_row(
-            "SELECT COUNT(*) FROM snapshots WHERE room_id = ?1",
-            rusqlite::params![&room_id],
-            |row| row.get(0),
-        )
-        .map_err(|e| e.to_string())?;
+    // The snapshots.room_id foreign key requires a room_settings row to
+    // exist; trg_snapshots_enforce_max_snapshots then caps this room's
+    // snapshots at its max_snapshots on insert. This also bootstraps
+    // `user_id` as moderator on a brand-new room, so the permission check
+    // right after sees the room's creator as authorized.
+    ensure_room_settings(&conn, &room_id, &user_id)?;
 
-    // If at limit, delete oldest snapshot
-    if count >= settings.max_snapshots {
-        conn.execute(
-            "DELETE FROM snapshots WHERE id = (SELECT id FROM snapshots WHERE room_id = ?1 ORDER BY created_at ASC LIMIT 1)",
-            rusqlite::params![&room_id],
-        )
-        .map_err(|e| e.to_string())?;
-    }
+    permissions::require_write(&conn, &room_id, &user_id)?;
 
-    // Insert new snapshot
     conn.execute(
         "INSERT INTO snapshots (id, room_id, name, description, thumbnail, created_by, created_at, data) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
-        rusqlite::params![&id, &room_id, &name, &description, &thumbnail, &created_by, timestamp, &data],
+        rusqlite::params![&id, &room_id, &name, &description, &stored_thumbnail, &created_by, timestamp, &stored_data],
     )
     .map_err(|e| e.to_string())?;
 
@@ -155,11 +190,13 @@ pub fn save_snapshot(
 }
 
 #[tauri::command]
-pub fn list_snapshots(room_id: String) -> Result<Vec<Snapshot>, String> {
-    let conn = DB.lock().map_err(|e| e.to_string())?;
+pub fn list_snapshots(room_id: String, user_id: String) -> Result<Vec<Snapshot>, String> {
+    let conn = MAIN_POOL.get().map_err(|e| e.to_string())?;
+
+    permissions::require_read(&conn, &room_id, &user_id)?;
 
     let mut stmt = conn
-        .prepare("SELECT id, room_id, name, description, thumbnail, created_by, created_at, '' as data FROM snapshots WHERE room_id = ?1 ORDER BY created_at DESC")
+        .prepare("SELECT id, room_id, name, description, thumbnail, created_by, created_at, expires_at, '' as data FROM snapshots WHERE room_id = ?1 ORDER BY created_at DESC")
         .map_err(|e| e.to_string())?;
 
     let snapshots = stmt
@@ -172,23 +209,32 @@ pub fn list_snapshots(room_id: String) -> Result<Vec<Snapshot>, String> {
                 thumbnail: row.get(4)?,
                 created_by: row.get(5)?,
                 created_at: row.get(6)?,
-                data: row.get(7)?,
+                expires_at: row.get(7)?,
+                data: row.get(8)?,
             })
         })
         .map_err(|e| e.to_string())?
         .collect::<Result<Vec<_>, _>>()
         .map_err(|e| e.to_string())?;
 
-    Ok(snapshots)
+    snapshots
+        .into_iter()
+        .map(|s| {
+            Ok(Snapshot {
+                thumbnail: maybe_decrypt_opt(s.thumbnail)?,
+                ..s
+            })
+        })
+        .collect()
 }
 
 #[tauri::command]
-pub fn load_snapshot(id: String) -> Result<Snapshot, String> {
-    let conn = DB.lock().map_err(|e| e.to_string())?;
+pub fn load_snapshot(id: String, user_id: String) -> Result<Snapshot, String> {
+    let conn = MAIN_POOL.get().map_err(|e| e.to_string())?;
 
     let snapshot = conn
         .query_row(
-            "SELECT id, room_id, name, description, thumbnail, created_by, created_at, data FROM snapshots WHERE id = ?1",
+            "SELECT id, room_id, name, description, thumbnail, created_by, created_at, expires_at, data FROM snapshots WHERE id = ?1",
             rusqlite::params![&id],
             |row| {
                 Ok(Snapshot {
@@ -199,18 +245,35 @@ pub fn load_snapshot(id: String) -> Result<Snapshot, String> {
                     thumbnail: row.get(4)?,
                     created_by: row.get(5)?,
                     created_at: row.get(6)?,
-                    data: row.get(7)?,
+                    expires_at: row.get(7)?,
+                    data: row.get(8)?,
                 })
             },
         )
         .map_err(|e| e.to_string())?;
 
-    Ok(snapshot)
+    permissions::require_read(&conn, &snapshot.room_id, &user_id)?;
+
+    Ok(Snapshot {
+        thumbnail: maybe_decrypt_opt(snapshot.thumbnail)?,
+        data: maybe_decrypt(&snapshot.data)?,
+        ..snapshot
+    })
 }
 
 #[tauri::command]
-pub fn delete_snapshot(id: String) -> Result<(), String> {
-    let conn = DB.lock().map_err(|e| e.to_string())?;
+pub fn delete_snapshot(id: String, user_id: String) -> Result<(), String> {
+    let conn = MAIN_POOL.get().map_err(|e| e.to_string())?;
+
+    let room_id: String = conn
+        .query_row(
+            "SELECT room_id FROM snapshots WHERE id = ?1",
+            rusqlite::params![&id],
+            |row| row.get(0),
+        )
+        .map_err(|e| e.to_string())?;
+
+    permissions::require_write(&conn, &room_id, &user_id)?;
 
     conn.execute(
         "DELETE FROM snapshots WHERE id = ?1",
@@ -224,10 +287,21 @@ pub fn delete_snapshot(id: String) -> Result<(), String> {
 #[tauri::command]
 pub fn update_snapshot_metadata(
     id: String,
+    user_id: String,
     name: String,
     description: String,
 ) -> Result<(), String> {
-    let conn = DB.lock().map_err(|e| e.to_string())?;
+    let conn = MAIN_POOL.get().map_err(|e| e.to_string())?;
+
+    let room_id: String = conn
+        .query_row(
+            "SELECT room_id FROM snapshots WHERE id = ?1",
+            rusqlite::params![&id],
+            |row| row.get(0),
+        )
+        .map_err(|e| e.to_string())?;
+
+    permissions::require_write(&conn, &room_id, &user_id)?;
 
     conn.execute(
         "UPDATE snapshots SET name = ?1, description = ?2 WHERE id = ?3",
@@ -241,12 +315,19 @@ pub fn update_snapshot_metadata(
 #[tauri::command]
 pub fn save_autosave_snapshot(
     room_id: String,
+    user_id: String,
     name: Option<String>,
     description: Option<String>,
     thumbnail: Option<String>,
     data: String,
 ) -> Result<String, String> {
-    let conn = DB.lock().map_err(|e| e.to_string())?;
+    let conn = MAIN_POOL.get().map_err(|e| e.to_string())?;
+
+    // Bootstrap on `user_id`, not the `__autosave__` sentinel — autosave is
+    // the normal first write to a room, and bootstrapping the sentinel would
+    // make it the room's sole moderator, locking the real user out forever.
+    ensure_room_settings(&conn, &room_id, &user_id)?;
+    permissions::require_write(&conn, &room_id, &user_id)?;
 
     let timestamp = SystemTime::now()
         .duration_since(UNIX_EPOCH)
@@ -255,7 +336,13 @@ pub fn save_autosave_snapshot(
 
     let final_name = name.unwrap_or_else(|| AUTOSAVE_DEFAULT_NAME.to_string());
     let final_description = description.unwrap_or_else(|| AUTOSAVE_DEFAULT_DESCRIPTION.to_string());
-    let final_thumbnail = thumbnail.unwrap_or_default();
+    let final_thumbnail = maybe_encrypt(&thumbnail.unwrap_or_default())?;
+    let stored_data = maybe_encrypt(&data)?;
+
+    // Named checkpoints (save_snapshot) never expire; autosaves expire after
+    // the room's snapshot_ttl, if one is configured.
+    let settings = get_room_settings_internal(&conn, &room_id)?;
+    let expires_at = settings.snapshot_ttl.map(|ttl| timestamp + ttl);
 
     let existing_id_result: Result<String, rusqlite::Error> = conn.query_row(
         "SELECT id FROM snapshots WHERE room_id = ?1 AND created_by = ?2 LIMIT 1",
@@ -266,13 +353,14 @@ pub fn save_autosave_snapshot(
     match existing_id_result {
         Ok(existing_id) => {
             conn.execute(
-                "UPDATE snapshots SET name = ?1, description = ?2, thumbnail = ?3, data = ?4, created_at = ?5 WHERE id = ?6",
+                "UPDATE snapshots SET name = ?1, description = ?2, thumbnail = ?3, data = ?4, created_at = ?5, expires_at = ?6 WHERE id = ?7",
                 rusqlite::params![
                     &final_name,
                     &final_description,
                     &final_thumbnail,
-                    &data,
+                    &stored_data,
                     timestamp,
+                    expires_at,
                     &existing_id,
                 ],
             )
@@ -283,7 +371,7 @@ pub fn save_autosave_snapshot(
         Err(rusqlite::Error::QueryReturnedNoRows) => {
             let id = uuid::Uuid::new_v4().to_string();
             conn.execute(
-                "INSERT INTO snapshots (id, room_id, name, description, thumbnail, created_by, created_at, data) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+                "INSERT INTO snapshots (id, room_id, name, description, thumbnail, created_by, created_at, expires_at, data) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
                 rusqlite::params![
                     &id,
                     &room_id,
@@ -292,7 +380,8 @@ pub fn save_autosave_snapshot(
                     &final_thumbnail,
                     AUTOSAVE_CREATED_BY,
                     timestamp,
-                    &data,
+                    expires_at,
+                    &stored_data,
                 ],
             )
             .map_err(|e| e.to_string())?;
@@ -303,20 +392,150 @@ pub fn save_autosave_snapshot(
     }
 }
 
+// Snapshot history commands
+
+#[tauri::command]
+pub fn list_snapshot_history(
+    room_id: String,
+    user_id: String,
+) -> Result<Vec<SnapshotHistoryEntry>, String> {
+    let conn = MAIN_POOL.get().map_err(|e| e.to_string())?;
+
+    permissions::require_read(&conn, &room_id, &user_id)?;
+
+    let mut stmt = conn
+        .prepare(
+            "SELECT id, snapshot_id, room_id, old_name, old_description, old_data, changed_at, change_kind
+             FROM snapshot_history WHERE room_id = ?1 ORDER BY changed_at DESC",
+        )
+        .map_err(|e| e.to_string())?;
+
+    let history = stmt
+        .query_map(rusqlite::params![&room_id], |row| {
+            Ok(SnapshotHistoryEntry {
+                id: row.get(0)?,
+                snapshot_id: row.get(1)?,
+                room_id: row.get(2)?,
+                old_name: row.get(3)?,
+                old_description: row.get(4)?,
+                old_data: row.get(5)?,
+                changed_at: row.get(6)?,
+                change_kind: row.get(7)?,
+            })
+        })
+        .map_err(|e| e.to_string())?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())?;
+
+    history
+        .into_iter()
+        .map(|h| {
+            Ok(SnapshotHistoryEntry {
+                old_data: maybe_decrypt(&h.old_data)?,
+                ..h
+            })
+        })
+        .collect()
+}
+
+#[tauri::command]
+pub fn restore_snapshot_from_history(history_id: i64, user_id: String) -> Result<String, String> {
+    let conn = MAIN_POOL.get().map_err(|e| e.to_string())?;
+
+    let entry = conn
+        .query_row(
+            "SELECT room_id, old_name, old_description, old_data FROM snapshot_history WHERE id = ?1",
+            rusqlite::params![history_id],
+            |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, Option<String>>(1)?,
+                    row.get::<_, Option<String>>(2)?,
+                    row.get::<_, String>(3)?,
+                ))
+            },
+        )
+        .map_err(|e| e.to_string())?;
+
+    let (room_id, old_name, old_description, old_data) = entry;
+
+    // Restoring re-inserts a snapshot, same as save_snapshot, so it's gated
+    // the same way.
+    ensure_room_settings(&conn, &room_id, &user_id)?;
+    permissions::require_write(&conn, &room_id, &user_id)?;
+
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as i64;
+
+    let id = uuid::Uuid::new_v4().to_string();
+
+    conn.execute(
+        "INSERT INTO snapshots (id, room_id, name, description, thumbnail, created_by, created_at, data) VALUES (?1, ?2, ?3, ?4, NULL, NULL, ?5, ?6)",
+        rusqlite::params![&id, &room_id, &old_name, &old_description, timestamp, &old_data],
+    )
+    .map_err(|e| e.to_string())?;
+
+    Ok(id)
+}
+
 // Room settings commands
 
+/// Inserts a default room_settings row for `room_id` if it doesn't already
+/// have one. Snapshots carry a foreign key to room_settings, so this must
+/// run before the first snapshot for a room is saved. When this is the
+/// room's very first row, `creator_user_id` is bootstrapped as its
+/// moderator — otherwise no one could ever pass `require_moderator`/
+/// `require_write` for a brand-new room.
+fn ensure_room_settings(
+    conn: &rusqlite::Connection,
+    room_id: &str,
+    creator_user_id: &str,
+) -> Result<(), String> {
+    conn.execute(
+        "INSERT OR IGNORE INTO room_settings (room_id) VALUES (?1)",
+        rusqlite::params![room_id],
+    )
+    .map_err(|e| e.to_string())?;
+
+    if conn.changes() > 0 {
+        bootstrap_room_creator(conn, room_id, creator_user_id)?;
+    }
+
+    Ok(())
+}
+
+/// Grants `user_id` full moderator + write access to a room. Only meant to
+/// be called the moment a room's settings row is first created.
+fn bootstrap_room_creator(
+    conn: &rusqlite::Connection,
+    room_id: &str,
+    user_id: &str,
+) -> Result<(), String> {
+    conn.execute(
+        "INSERT OR IGNORE INTO room_members (room_id, user_id, can_read, can_write, is_moderator, is_admin)
+         VALUES (?1, ?2, 1, 1, 1, 0)",
+        rusqlite::params![room_id, user_id],
+    )
+    .map_err(|e| e.to_string())?;
+
+    Ok(())
+}
+
 fn get_room_settings_internal(
     conn: &rusqlite::Connection,
     room_id: &str,
 ) -> Result<RoomSettings, String> {
     conn.query_row(
-        "SELECT room_id, max_snapshots, auto_save_interval FROM room_settings WHERE room_id = ?1",
+        "SELECT room_id, max_snapshots, auto_save_interval, snapshot_ttl FROM room_settings WHERE room_id = ?1",
         rusqlite::params![room_id],
         |row| {
             Ok(RoomSettings {
                 room_id: row.get(0)?,
                 max_snapshots: row.get(1)?,
                 auto_save_interval: row.get(2)?,
+                snapshot_ttl: row.get(3)?,
             })
         },
     )
@@ -326,30 +545,200 @@ fn get_room_settings_internal(
             room_id: room_id.to_string(),
             max_snapshots: 10,
             auto_save_interval: 60,
+            snapshot_ttl: None,
         })
     })
 }
 
 #[tauri::command]
 pub fn get_room_settings(room_id: String) -> Result<RoomSettings, String> {
-    let conn = DB.lock().map_err(|e| e.to_string())?;
+    let conn = MAIN_POOL.get().map_err(|e| e.to_string())?;
     get_room_settings_internal(&conn, &room_id)
 }
 
 #[tauri::command]
 pub fn update_room_settings(
     room_id: String,
+    user_id: String,
     max_snapshots: i32,
     auto_save_interval: i32,
+    snapshot_ttl: Option<i64>,
 ) -> Result<(), String> {
-    let conn = DB.lock().map_err(|e| e.to_string())?;
+    let conn = MAIN_POOL.get().map_err(|e| e.to_string())?;
+
+    // A brand-new room has no moderator yet, so bootstrap `user_id` as one
+    // before the room_settings row exists; require_moderator then passes for
+    // them (and only them, until they grant others permissions).
+    ensure_room_settings(&conn, &room_id, &user_id)?;
+    permissions::require_moderator(&conn, &room_id, &user_id)?;
+
+    conn.execute(
+        "INSERT INTO room_settings (room_id, max_snapshots, auto_save_interval, snapshot_ttl) VALUES (?1, ?2, ?3, ?4)
+         ON CONFLICT(room_id) DO UPDATE SET max_snapshots = ?2, auto_save_interval = ?3, snapshot_ttl = ?4",
+        rusqlite::params![&room_id, max_snapshots, auto_save_interval, snapshot_ttl],
+    )
+    .map_err(|e| e.to_string())?;
+
+    Ok(())
+}
+
+#[tauri::command]
+pub fn purge_expired_snapshots() -> Result<usize, String> {
+    let conn = MAIN_POOL.get().map_err(|e| e.to_string())?;
+    crate::db::purge_expired_snapshots(&conn).map_err(|e| e.to_string())
+}
+
+// Encryption commands
+
+#[tauri::command]
+pub fn set_encryption_passphrase(passphrase: String) -> Result<(), String> {
+    let conn = MAIN_POOL.get().map_err(|e| e.to_string())?;
+
+    let already_set: Option<i64> = conn
+        .query_row(
+            "SELECT id FROM encryption_settings WHERE id = 1",
+            [],
+            |row| row.get(0),
+        )
+        .optional()
+        .map_err(|e| e.to_string())?;
+
+    if already_set.is_some() {
+        return Err("an encryption passphrase is already set; use unlock instead".to_string());
+    }
+
+    let salt = crypto::generate_salt();
+    let key = crypto::derive_key(&passphrase, &salt);
+    let verifier = crypto::make_verifier(&key)?;
 
     conn.execute(
-        "INSERT INTO room_settings (room_id, max_snapshots, auto_save_interval) VALUES (?1, ?2, ?3) 
-         ON CONFLICT(room_id) DO UPDATE SET max_snapshots = ?2, auto_save_interval = ?3",
-        rusqlite::params![&room_id, max_snapshots, auto_save_interval],
+        "INSERT INTO encryption_settings (id, salt, verifier) VALUES (1, ?1, ?2)",
+        rusqlite::params![&salt, &verifier],
     )
     .map_err(|e| e.to_string())?;
 
+    *crypto::SESSION_KEY.lock().map_err(|e| e.to_string())? = Some(key);
+
     Ok(())
 }
+
+#[tauri::command]
+pub fn unlock(passphrase: String) -> Result<(), String> {
+    let conn = MAIN_POOL.get().map_err(|e| e.to_string())?;
+
+    let (salt, verifier): (Vec<u8>, Vec<u8>) = conn
+        .query_row(
+            "SELECT salt, verifier FROM encryption_settings WHERE id = 1",
+            [],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .map_err(|_| "no encryption passphrase has been set".to_string())?;
+
+    let key = crypto::derive_key(&passphrase, &salt);
+    if !crypto::verify_key(&key, &verifier) {
+        return Err("incorrect passphrase".to_string());
+    }
+
+    *crypto::SESSION_KEY.lock().map_err(|e| e.to_string())? = Some(key);
+
+    Ok(())
+}
+
+// Room membership and permission commands
+
+#[tauri::command]
+pub fn set_room_permission(
+    room_id: String,
+    caller_id: String,
+    user_id: String,
+    can_read: bool,
+    can_write: bool,
+    is_moderator: bool,
+    is_admin: bool,
+    expires_at: Option<i64>,
+) -> Result<(), String> {
+    let conn = MAIN_POOL.get().map_err(|e| e.to_string())?;
+
+    // A brand-new room has no moderator yet, so bootstrap `caller_id` as one
+    // before the check below; on an existing room this is a no-op and
+    // require_moderator gates the grant for real.
+    ensure_room_settings(&conn, &room_id, &caller_id)?;
+    permissions::require_moderator(&conn, &room_id, &caller_id)?;
+
+    conn.execute(
+        "INSERT INTO room_members (room_id, user_id, can_read, can_write, is_moderator, is_admin, expires_at)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
+         ON CONFLICT(room_id, user_id) DO UPDATE SET
+            can_read = ?3, can_write = ?4, is_moderator = ?5, is_admin = ?6, expires_at = ?7",
+        rusqlite::params![&room_id, &user_id, can_read, can_write, is_moderator, is_admin, expires_at],
+    )
+    .map_err(|e| e.to_string())?;
+
+    Ok(())
+}
+
+/// Sets the read/write access a room grants to users with no explicit
+/// `room_members` row. Requires moderating the room (same bootstrap-on-
+/// brand-new-room exception as `set_room_permission`).
+#[tauri::command]
+pub fn set_room_defaults(
+    room_id: String,
+    caller_id: String,
+    default_can_read: bool,
+    default_can_write: bool,
+) -> Result<(), String> {
+    let conn = MAIN_POOL.get().map_err(|e| e.to_string())?;
+
+    ensure_room_settings(&conn, &room_id, &caller_id)?;
+    permissions::require_moderator(&conn, &room_id, &caller_id)?;
+
+    conn.execute(
+        "INSERT INTO room_defaults (room_id, default_can_read, default_can_write)
+         VALUES (?1, ?2, ?3)
+         ON CONFLICT(room_id) DO UPDATE SET
+            default_can_read = ?2, default_can_write = ?3",
+        rusqlite::params![&room_id, default_can_read, default_can_write],
+    )
+    .map_err(|e| e.to_string())?;
+
+    Ok(())
+}
+
+#[tauri::command]
+pub fn list_room_members(room_id: String) -> Result<Vec<RoomMember>, String> {
+    let conn = MAIN_POOL.get().map_err(|e| e.to_string())?;
+
+    let mut stmt = conn
+        .prepare(
+            "SELECT room_id, user_id, can_read, can_write, is_moderator, is_admin, expires_at
+             FROM room_members WHERE room_id = ?1 ORDER BY user_id",
+        )
+        .map_err(|e| e.to_string())?;
+
+    let members = stmt
+        .query_map(rusqlite::params![&room_id], |row| {
+            Ok(RoomMember {
+                room_id: row.get(0)?,
+                user_id: row.get(1)?,
+                can_read: row.get(2)?,
+                can_write: row.get(3)?,
+                is_moderator: row.get(4)?,
+                is_admin: row.get(5)?,
+                expires_at: row.get(6)?,
+            })
+        })
+        .map_err(|e| e.to_string())?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())?;
+
+    Ok(members)
+}
+
+#[tauri::command]
+pub fn get_effective_permissions(
+    room_id: String,
+    user_id: String,
+) -> Result<EffectivePermissions, String> {
+    let conn = MAIN_POOL.get().map_err(|e| e.to_string())?;
+    permissions::get_effective_permissions(&conn, &room_id, &user_id)
+}
@@ -0,0 +1,97 @@
+use aes_gcm::aead::{Aead, KeyInit, OsRng};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use once_cell::sync::Lazy;
+use pbkdf2::pbkdf2_hmac;
+use rand::RngCore;
+use sha2::Sha256;
+use std::sync::Mutex;
+
+const SALT_LEN: usize = 16;
+const IV_LEN: usize = 12;
+const PBKDF2_ROUNDS: u32 = 210_000;
+// Fixed plaintext we encrypt with the derived key and store alongside the
+// salt. A passphrase is "correct" iff it decrypts this back unchanged,
+// without ever storing the key itself.
+const VERIFIER_PLAINTEXT: &[u8] = b"excalidraw-encryption-verifier";
+
+/// The AES-256 key derived from the user's passphrase, held only for the
+/// lifetime of the session. `None` means the database is locked.
+pub static SESSION_KEY: Lazy<Mutex<Option<[u8; 32]>>> = Lazy::new(|| Mutex::new(None));
+
+pub fn generate_salt() -> Vec<u8> {
+    let mut salt = vec![0u8; SALT_LEN];
+    OsRng.fill_bytes(&mut salt);
+    salt
+}
+
+pub fn derive_key(passphrase: &str, salt: &[u8]) -> [u8; 32] {
+    let mut key = [0u8; 32];
+    pbkdf2_hmac::<Sha256>(passphrase.as_bytes(), salt, PBKDF2_ROUNDS, &mut key);
+    key
+}
+
+pub fn make_verifier(key: &[u8; 32]) -> Result<Vec<u8>, String> {
+    encrypt_bytes(key, VERIFIER_PLAINTEXT)
+}
+
+pub fn verify_key(key: &[u8; 32], verifier: &[u8]) -> bool {
+    decrypt_bytes(key, verifier)
+        .map(|plain| plain == VERIFIER_PLAINTEXT)
+        .unwrap_or(false)
+}
+
+/// Returns the currently unlocked session key, or an error if the database
+/// hasn't been unlocked yet.
+pub fn current_key() -> Result<[u8; 32], String> {
+    SESSION_KEY
+        .lock()
+        .map_err(|e| e.to_string())?
+        .ok_or_else(|| "database is locked; call unlock first".to_string())
+}
+
+fn encrypt_bytes(key: &[u8; 32], plaintext: &[u8]) -> Result<Vec<u8>, String> {
+    let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(key));
+
+    let mut iv = vec![0u8; IV_LEN];
+    OsRng.fill_bytes(&mut iv);
+    let nonce = Nonce::from_slice(&iv);
+
+    let ciphertext = cipher
+        .encrypt(nonce, plaintext)
+        .map_err(|e| e.to_string())?;
+
+    // Stored as iv || ciphertext, where ciphertext already has the GCM tag
+    // appended by the `aes-gcm` crate.
+    let mut out = iv;
+    out.extend_from_slice(&ciphertext);
+    Ok(out)
+}
+
+fn decrypt_bytes(key: &[u8; 32], data: &[u8]) -> Result<Vec<u8>, String> {
+    if data.len() < IV_LEN {
+        return Err("ciphertext too short".to_string());
+    }
+
+    let (iv, ciphertext) = data.split_at(IV_LEN);
+    let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(key));
+    let nonce = Nonce::from_slice(iv);
+
+    cipher
+        .decrypt(nonce, ciphertext)
+        .map_err(|_| "decryption failed (wrong passphrase?)".to_string())
+}
+
+/// Encrypts `plaintext` under `key`, returning `base64(iv || ciphertext || tag)`.
+pub fn encrypt_string(key: &[u8; 32], plaintext: &str) -> Result<String, String> {
+    let bytes = encrypt_bytes(key, plaintext.as_bytes())?;
+    Ok(STANDARD.encode(bytes))
+}
+
+/// Inverse of [`encrypt_string`].
+pub fn decrypt_string(key: &[u8; 32], encoded: &str) -> Result<String, String> {
+    let bytes = STANDARD.decode(encoded).map_err(|e| e.to_string())?;
+    let plain = decrypt_bytes(key, &bytes)?;
+    String::from_utf8(plain).map_err(|e| e.to_string())
+}
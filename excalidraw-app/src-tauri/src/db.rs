@@ -1,8 +1,13 @@
 use once_cell::sync::Lazy;
+use r2d2::{Pool, PooledConnection};
+use r2d2_sqlite::SqliteConnectionManager;
 use rusqlite::{Connection, Result};
 use serde::{Deserialize, Serialize};
 use std::path::PathBuf;
-use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+pub type DbPool = Pool<SqliteConnectionManager>;
+pub type PooledConn = PooledConnection<SqliteConnectionManager>;
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Drawing {
@@ -22,20 +27,50 @@ pub struct Snapshot {
     pub thumbnail: Option<String>,
     pub created_by: Option<String>,
     pub created_at: i64,
+    pub expires_at: Option<i64>,
     pub data: String,
 }
 
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SnapshotHistoryEntry {
+    pub id: i64,
+    pub snapshot_id: String,
+    pub room_id: String,
+    pub old_name: Option<String>,
+    pub old_description: Option<String>,
+    pub old_data: String,
+    pub changed_at: i64,
+    pub change_kind: String,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct RoomSettings {
     pub room_id: String,
     pub max_snapshots: i32,
     pub auto_save_interval: i32,
+    /// Seconds an autosave snapshot lives before `purge_expired_snapshots`
+    /// reaps it. `None` means autosaves in this room never expire. Named
+    /// checkpoints are never subject to this regardless of its value.
+    pub snapshot_ttl: Option<i64>,
 }
 
-pub static DB: Lazy<Mutex<Connection>> = Lazy::new(|| {
-    let conn = Connection::open(get_db_path()).expect("Failed to open database");
-    init_db(&conn).expect("Failed to initialize database");
-    Mutex::new(conn)
+pub static MAIN_POOL: Lazy<DbPool> = Lazy::new(|| {
+    let manager = SqliteConnectionManager::file(get_db_path())
+        .with_init(|conn| {
+            conn.busy_timeout(Duration::from_secs(5))?;
+            conn.pragma_update(None, "journal_mode", "WAL")?;
+            conn.pragma_update(None, "foreign_keys", "ON")?;
+            Ok(())
+        });
+
+    let pool = Pool::new(manager).expect("Failed to create database connection pool");
+
+    // Run migrations once, against a single checked-out connection, before
+    // any command can race it with a pooled one.
+    let conn = pool.get().expect("Failed to get connection for migrations");
+    run_migrations(&conn).expect("Failed to migrate database");
+
+    pool
 });
 
 fn get_db_path() -> PathBuf {
@@ -50,40 +85,207 @@ fn get_db_path() -> PathBuf {
     app_dir.join("drawings.db")
 }
 
-fn init_db(conn: &Connection) -> Result<()> {
-    conn.execute(
-        "CREATE TABLE IF NOT EXISTS drawings (
-            id TEXT PRIMARY KEY,
-            name TEXT NOT NULL,
-            data TEXT NOT NULL,
-            created_at INTEGER NOT NULL,
-            updated_at INTEGER NOT NULL
-        )",
-        [],
-    )?;
+/// Ordered, append-only list of up-migrations. Migration `N` is applied iff
+/// the database's `user_version` is `<= N`. Never edit a migration once it
+/// has shipped; append a new one instead, even to fix an earlier mistake.
+const MIGRATIONS: &[&str] = &[
+    // 0: initial schema
+    "CREATE TABLE IF NOT EXISTS drawings (
+        id TEXT PRIMARY KEY,
+        name TEXT NOT NULL,
+        data TEXT NOT NULL,
+        created_at INTEGER NOT NULL,
+        updated_at INTEGER NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS snapshots (
+        id TEXT PRIMARY KEY,
+        room_id TEXT NOT NULL,
+        name TEXT,
+        description TEXT,
+        thumbnail TEXT,
+        created_by TEXT,
+        created_at INTEGER NOT NULL,
+        data TEXT NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS room_settings (
+        room_id TEXT PRIMARY KEY,
+        max_snapshots INTEGER DEFAULT 10,
+        auto_save_interval INTEGER DEFAULT 60
+    );",
+    // 1: enforce max_snapshots and updated_at via triggers instead of app code.
+    // `snapshots` is rebuilt with a FK to `room_settings` (SQLite can't ALTER
+    // TABLE ... ADD CONSTRAINT), backfilling a settings row for any room_id
+    // that doesn't have one yet so existing data survives the FK.
+    "ALTER TABLE snapshots RENAME TO snapshots_old;
+    CREATE TABLE snapshots (
+        id TEXT PRIMARY KEY,
+        room_id TEXT NOT NULL REFERENCES room_settings(room_id) ON DELETE CASCADE,
+        name TEXT,
+        description TEXT,
+        thumbnail TEXT,
+        created_by TEXT,
+        created_at INTEGER NOT NULL,
+        data TEXT NOT NULL
+    );
+    INSERT OR IGNORE INTO room_settings (room_id)
+        SELECT DISTINCT room_id FROM snapshots_old;
+    INSERT INTO snapshots SELECT * FROM snapshots_old;
+    DROP TABLE snapshots_old;
 
-    conn.execute(
-        "CREATE TABLE IF NOT EXISTS snapshots (
-            id TEXT PRIMARY KEY,
-            room_id TEXT NOT NULL,
-            name TEXT,
-            description TEXT,
-            thumbnail TEXT,
-            created_by TEXT,
-            created_at INTEGER NOT NULL,
-            data TEXT NOT NULL
-        )",
-        [],
-    )?;
+    CREATE TRIGGER trg_snapshots_enforce_max_snapshots
+    AFTER INSERT ON snapshots
+    BEGIN
+        DELETE FROM snapshots
+        WHERE room_id = NEW.room_id
+          AND id NOT IN (
+              SELECT id FROM snapshots
+              WHERE room_id = NEW.room_id
+              ORDER BY created_at DESC
+              LIMIT (SELECT max_snapshots FROM room_settings WHERE room_id = NEW.room_id)
+          );
+    END;
+
+    CREATE TRIGGER trg_drawings_set_updated_at
+    AFTER UPDATE ON drawings
+    WHEN NEW.updated_at IS OLD.updated_at
+    BEGIN
+        UPDATE drawings SET updated_at = CAST(strftime('%s', 'now') AS INTEGER) WHERE id = NEW.id;
+    END;",
+    // 2: snapshot edit/delete history. BEFORE triggers capture OLD before the
+    // mutation lands, including edits made by trg_snapshots_enforce_max_snapshots'
+    // own pruning deletes, so nothing is lost.
+    "CREATE TABLE snapshot_history (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        snapshot_id TEXT NOT NULL,
+        room_id TEXT NOT NULL,
+        old_name TEXT,
+        old_description TEXT,
+        old_data TEXT NOT NULL,
+        changed_at INTEGER NOT NULL,
+        change_kind TEXT NOT NULL
+    );
+
+    CREATE TRIGGER trg_snapshots_history_update
+    BEFORE UPDATE ON snapshots
+    WHEN NEW.data IS NOT OLD.data
+      OR NEW.name IS NOT OLD.name
+      OR NEW.description IS NOT OLD.description
+    BEGIN
+        INSERT INTO snapshot_history (snapshot_id, room_id, old_name, old_description, old_data, changed_at, change_kind)
+        VALUES (OLD.id, OLD.room_id, OLD.name, OLD.description, OLD.data, CAST(strftime('%s', 'now') AS INTEGER), 'update');
+    END;
+
+    CREATE TRIGGER trg_snapshots_history_delete
+    BEFORE DELETE ON snapshots
+    BEGIN
+        INSERT INTO snapshot_history (snapshot_id, room_id, old_name, old_description, old_data, changed_at, change_kind)
+        VALUES (OLD.id, OLD.room_id, OLD.name, OLD.description, OLD.data, CAST(strftime('%s', 'now') AS INTEGER), 'delete');
+    END;",
+    // 3: at-rest encryption. `salt`/`verifier` let us confirm a passphrase is
+    // correct without ever persisting the derived key; the `id = 1` check
+    // keeps this a singleton table, matching there being one passphrase per
+    // local database.
+    "CREATE TABLE encryption_settings (
+        id INTEGER PRIMARY KEY CHECK (id = 1),
+        salt BLOB NOT NULL,
+        verifier BLOB NOT NULL
+    );",
+    // 4: time-based snapshot expiry. `expires_at` is NULL for named
+    // checkpoints (non-expiring) and stamped by save_autosave_snapshot for
+    // autosaves when the room has a snapshot_ttl configured.
+    "ALTER TABLE snapshots ADD COLUMN expires_at INTEGER;
+    ALTER TABLE room_settings ADD COLUMN snapshot_ttl INTEGER;",
+    // 5: room membership and effective-permission model. `room_members` is a
+    // per-user grant, `room_defaults` sets what non-members may do, and
+    // `global_roles` carries server-wide admins/bans. `effective_permissions`
+    // coalesces a membership row with its room's defaults and the member's
+    // global role into a single row per (room_id, user_id); it only covers
+    // known members, so callers fall back to room_defaults (and deny banned
+    // users / grant global admins everywhere) for everyone else.
+    "CREATE TABLE room_members (
+        room_id TEXT NOT NULL REFERENCES room_settings(room_id) ON DELETE CASCADE,
+        user_id TEXT NOT NULL,
+        can_read INTEGER NOT NULL DEFAULT 1,
+        can_write INTEGER NOT NULL DEFAULT 0,
+        is_moderator INTEGER NOT NULL DEFAULT 0,
+        is_admin INTEGER NOT NULL DEFAULT 0,
+        expires_at INTEGER,
+        PRIMARY KEY (room_id, user_id)
+    );
+
+    CREATE TABLE room_defaults (
+        room_id TEXT PRIMARY KEY REFERENCES room_settings(room_id) ON DELETE CASCADE,
+        default_can_read INTEGER NOT NULL DEFAULT 1,
+        default_can_write INTEGER NOT NULL DEFAULT 0
+    );
+
+    CREATE TABLE global_roles (
+        user_id TEXT PRIMARY KEY,
+        is_admin INTEGER NOT NULL DEFAULT 0,
+        is_banned INTEGER NOT NULL DEFAULT 0
+    );
+
+    CREATE VIEW effective_permissions AS
+    SELECT
+        m.room_id AS room_id,
+        m.user_id AS user_id,
+        CASE WHEN COALESCE(g.is_banned, 0) = 1 THEN 0
+             WHEN m.expires_at IS NOT NULL AND m.expires_at < CAST(strftime('%s', 'now') AS INTEGER)
+                  THEN COALESCE(d.default_can_read, 1)
+             ELSE MAX(m.can_read, COALESCE(d.default_can_read, 1))
+        END AS can_read,
+        CASE WHEN COALESCE(g.is_banned, 0) = 1 THEN 0
+             WHEN m.expires_at IS NOT NULL AND m.expires_at < CAST(strftime('%s', 'now') AS INTEGER)
+                  THEN COALESCE(d.default_can_write, 0)
+             ELSE MAX(m.can_write, COALESCE(g.is_admin, 0), COALESCE(d.default_can_write, 0))
+        END AS can_write,
+        CASE WHEN COALESCE(g.is_banned, 0) = 1 THEN 0
+             WHEN m.expires_at IS NOT NULL AND m.expires_at < CAST(strftime('%s', 'now') AS INTEGER) THEN 0
+             ELSE m.is_moderator
+        END AS is_moderator,
+        CASE WHEN COALESCE(g.is_banned, 0) = 1 THEN 0
+             WHEN m.expires_at IS NOT NULL AND m.expires_at < CAST(strftime('%s', 'now') AS INTEGER)
+                  THEN COALESCE(g.is_admin, 0)
+             ELSE MAX(m.is_admin, COALESCE(g.is_admin, 0))
+        END AS is_admin
+    FROM room_members m
+    LEFT JOIN room_defaults d ON d.room_id = m.room_id
+    LEFT JOIN global_roles g ON g.user_id = m.user_id;",
+];
+
+/// Deletes every snapshot whose `expires_at` has passed. Named checkpoints
+/// (`expires_at IS NULL`) are never touched.
+pub fn purge_expired_snapshots(conn: &Connection) -> Result<usize> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as i64;
 
     conn.execute(
-        "CREATE TABLE IF NOT EXISTS room_settings (
-            room_id TEXT PRIMARY KEY,
-            max_snapshots INTEGER DEFAULT 10,
-            auto_save_interval INTEGER DEFAULT 60
-        )",
-        [],
-    )?;
+        "DELETE FROM snapshots WHERE expires_at IS NOT NULL AND expires_at < ?1",
+        [now],
+    )
+}
+
+/// Runs every migration in `MIGRATIONS` whose index is `>= user_version`,
+/// inside a single transaction, then advances `user_version` to
+/// `MIGRATIONS.len()`. A failed migration rolls back the whole batch and
+/// leaves `user_version` untouched, so startup aborts rather than leaving
+/// the database half-migrated.
+fn run_migrations(conn: &Connection) -> Result<()> {
+    let user_version: i64 = conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+    let current = user_version as usize;
+
+    if current >= MIGRATIONS.len() {
+        return Ok(());
+    }
+
+    let tx = conn.unchecked_transaction()?;
+    for migration in &MIGRATIONS[current..] {
+        tx.execute_batch(migration)?;
+    }
+    tx.pragma_update(None, "user_version", MIGRATIONS.len() as i64)?;
+    tx.commit()?;
 
     Ok(())
 }
@@ -1,11 +1,30 @@
 mod commands;
+mod crypto;
 mod db;
+mod permissions;
+
+use std::thread;
+use std::time::Duration;
+
+const SNAPSHOT_SWEEP_INTERVAL: Duration = Duration::from_secs(300);
+
+/// Periodically reaps expired autosave snapshots, so they are removed even
+/// when nobody calls the `purge_expired_snapshots` command.
+fn spawn_snapshot_sweeper() {
+    thread::spawn(|| loop {
+        thread::sleep(SNAPSHOT_SWEEP_INTERVAL);
+        if let Ok(conn) = db::MAIN_POOL.get() {
+            let _ = db::purge_expired_snapshots(&conn);
+        }
+    });
+}
 
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run() {
-    // Initialize the database (lazy static will create it on first access)
-    let _ = &*db::DB;
-    
+    // Initialize the connection pool (lazy static will create it on first access)
+    let _ = &*db::MAIN_POOL;
+    spawn_snapshot_sweeper();
+
     tauri::Builder::default()
         .plugin(tauri_plugin_opener::init())
         .invoke_handler(tauri::generate_handler![
@@ -19,8 +38,17 @@ pub fn run() {
             commands::load_snapshot,
             commands::delete_snapshot,
             commands::update_snapshot_metadata,
+            commands::list_snapshot_history,
+            commands::restore_snapshot_from_history,
             commands::get_room_settings,
             commands::update_room_settings,
+            commands::set_encryption_passphrase,
+            commands::unlock,
+            commands::purge_expired_snapshots,
+            commands::set_room_permission,
+            commands::set_room_defaults,
+            commands::list_room_members,
+            commands::get_effective_permissions,
         ])
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
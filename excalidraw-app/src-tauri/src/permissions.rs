@@ -0,0 +1,148 @@
+use rusqlite::{Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RoomMember {
+    pub room_id: String,
+    pub user_id: String,
+    pub can_read: bool,
+    pub can_write: bool,
+    pub is_moderator: bool,
+    pub is_admin: bool,
+    pub expires_at: Option<i64>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct EffectivePermissions {
+    pub room_id: String,
+    pub user_id: String,
+    pub can_read: bool,
+    pub can_write: bool,
+    pub is_moderator: bool,
+    pub is_admin: bool,
+}
+
+impl EffectivePermissions {
+    fn none(room_id: &str, user_id: &str) -> Self {
+        EffectivePermissions {
+            room_id: room_id.to_string(),
+            user_id: user_id.to_string(),
+            can_read: false,
+            can_write: false,
+            is_moderator: false,
+            is_admin: false,
+        }
+    }
+
+    fn global_admin(room_id: &str, user_id: &str) -> Self {
+        EffectivePermissions {
+            room_id: room_id.to_string(),
+            user_id: user_id.to_string(),
+            can_read: true,
+            can_write: true,
+            is_moderator: true,
+            is_admin: true,
+        }
+    }
+}
+
+/// Resolves what `user_id` may do in `room_id`, coalescing (in order)
+/// server-wide bans, server-wide admins, the `effective_permissions` view for
+/// known members, and finally the room's defaults for everyone else.
+pub fn get_effective_permissions(
+    conn: &Connection,
+    room_id: &str,
+    user_id: &str,
+) -> Result<EffectivePermissions, String> {
+    let global_role: Option<(bool, bool)> = conn
+        .query_row(
+            "SELECT is_admin, is_banned FROM global_roles WHERE user_id = ?1",
+            rusqlite::params![user_id],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .optional()
+        .map_err(|e| e.to_string())?;
+
+    if let Some((_, true)) = global_role {
+        return Ok(EffectivePermissions::none(room_id, user_id));
+    }
+    if let Some((true, _)) = global_role {
+        return Ok(EffectivePermissions::global_admin(room_id, user_id));
+    }
+
+    let member_row: Option<(bool, bool, bool, bool)> = conn
+        .query_row(
+            "SELECT can_read, can_write, is_moderator, is_admin FROM effective_permissions
+             WHERE room_id = ?1 AND user_id = ?2",
+            rusqlite::params![room_id, user_id],
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)),
+        )
+        .optional()
+        .map_err(|e| e.to_string())?;
+
+    if let Some((can_read, can_write, is_moderator, is_admin)) = member_row {
+        return Ok(EffectivePermissions {
+            room_id: room_id.to_string(),
+            user_id: user_id.to_string(),
+            can_read,
+            can_write,
+            is_moderator,
+            is_admin,
+        });
+    }
+
+    let (default_can_read, default_can_write): (bool, bool) = conn
+        .query_row(
+            "SELECT default_can_read, default_can_write FROM room_defaults WHERE room_id = ?1",
+            rusqlite::params![room_id],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .optional()
+        .map_err(|e| e.to_string())?
+        .unwrap_or((true, false));
+
+    Ok(EffectivePermissions {
+        room_id: room_id.to_string(),
+        user_id: user_id.to_string(),
+        can_read: default_can_read,
+        can_write: default_can_write,
+        is_moderator: false,
+        is_admin: false,
+    })
+}
+
+/// Returns an authorization error unless `user_id` can read `room_id`.
+pub fn require_read(conn: &Connection, room_id: &str, user_id: &str) -> Result<(), String> {
+    let perms = get_effective_permissions(conn, room_id, user_id)?;
+    if perms.can_read {
+        Ok(())
+    } else {
+        Err(format!(
+            "user '{user_id}' is not authorized to read room '{room_id}'"
+        ))
+    }
+}
+
+/// Returns an authorization error unless `user_id` can write in `room_id`.
+pub fn require_write(conn: &Connection, room_id: &str, user_id: &str) -> Result<(), String> {
+    let perms = get_effective_permissions(conn, room_id, user_id)?;
+    if perms.can_write {
+        Ok(())
+    } else {
+        Err(format!(
+            "user '{user_id}' is not authorized to write to room '{room_id}'"
+        ))
+    }
+}
+
+/// Returns an authorization error unless `user_id` moderates `room_id`.
+pub fn require_moderator(conn: &Connection, room_id: &str, user_id: &str) -> Result<(), String> {
+    let perms = get_effective_permissions(conn, room_id, user_id)?;
+    if perms.is_moderator || perms.is_admin {
+        Ok(())
+    } else {
+        Err(format!(
+            "user '{user_id}' is not authorized to moderate room '{room_id}'"
+        ))
+    }
+}